@@ -5,20 +5,62 @@
 use assets::Asset;
 use atlas::{self, Priority};
 use context::Context;
-use display_list::{Au, BLACK, ClippingRegion, Color, DisplayItem, TRANSPARENT_GREEN, WHITE};
+use display_list::{Au, BLACK, ClippingRegion, Color, ComplexClipRegion, DisplayItem};
+use display_list::{BorderSide, BorderStyle, GradientKind, GradientStop, WHITE, YuvColorSpace};
 
-use euclid::{Point2D, Point3D, Rect, Size2D};
+use euclid::{Point2D, Point3D, Rect, SideOffsets2D, Size2D};
 use std::iter;
 
 const NEAR_DEPTH_VALUE: f32 = -0.5;
 const FAR_DEPTH_VALUE: f32 = 0.5;
 
+// Sentinel mask coordinate for vertices with no clip. It is deliberately outside the `[0, 1]` UV
+// range so the fragment path can recognize it (`mask_coord.x < 0.0`) and skip the clip multiply
+// entirely, rather than sampling — and trusting the contents of — some particular atlas texel.
+const OPAQUE_MASK_COORD: Point2D<f32> = Point2D { x: -1.0, y: -1.0 };
+
+// Number of texels in a baked 1-D gradient ramp.
+const GRADIENT_RAMP_LENGTH: u32 = 256;
+
+// Selects the fragment path a quad is drawn with. Parallel to the other per-vertex channels; every
+// vertex of a quad carries the same material so batches can later be keyed by it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Material {
+    // Interpolated vertex color, no texture.
+    SolidColor,
+    // Glyph coverage sampled from the atlas and gamma-corrected against the vertex color.
+    Text,
+    // Alpha sampled from a single-channel atlas tile, tinted by the vertex color (box shadows).
+    Mask,
+    // Color sampled from a baked 1-D ramp at the per-vertex gradient parameter.
+    Gradient,
+    // A border edge: solid, or with fragments discarded in the gaps of a dash/dot pattern.
+    BorderEdge,
+    // A border corner annulus, anti-aliased against its inner and outer radii.
+    BorderCorner,
+    // An RGBA bitmap sampled from the atlas and tinted by the vertex color.
+    Image,
+    // A three-plane YUV frame converted to RGB with the given color space's matrix. The Y plane is
+    // carried in the texture channel, the U and V planes in the edge and corner channels.
+    YuvImage(YuvColorSpace),
+}
+
 pub struct Batch {
     pub vertices: Vec<Point3D<f32>>,
     pub colors: Vec<Color>,
     pub buffer_gamma: Vec<Point2D<f32>>,
     pub texture_coords: Vec<Point2D<f32>>,
+    pub mask_coords: Vec<Point2D<f32>>,
+    pub materials: Vec<Material>,
+    // For `BorderEdge`, the per-vertex (position-along-edge, dash-period); for `BorderCorner`, the
+    // per-vertex position relative to the corner center; for `YuvImage`, the U and V plane texture
+    // coordinates respectively. Dummy elsewhere.
+    pub edge_params: Vec<Point2D<f32>>,
+    pub corner_params: Vec<Point2D<f32>>,
     pub elements: Vec<u32>,
+    // Depth the next quad's vertices are emitted at. The `Batcher` stamps it per item from the
+    // display-list draw index so later items sit nearer and win the depth test (painter's order).
+    depth: f32,
 }
 
 impl Batch {
@@ -28,10 +70,24 @@ impl Batch {
             colors: Vec::new(),
             buffer_gamma: Vec::new(),
             texture_coords: Vec::new(),
+            mask_coords: Vec::new(),
+            materials: Vec::new(),
+            edge_params: Vec::new(),
+            corner_params: Vec::new(),
             elements: Vec::new(),
+            depth: NEAR_DEPTH_VALUE,
         }
     }
 
+    fn add_material(&mut self, count: usize, material: Material) {
+        self.materials.extend(iter::repeat(material).take(count))
+    }
+
+    fn add_dummy_border_params(&mut self, count: usize) {
+        self.edge_params.extend(iter::repeat(Point2D::new(0.0, 0.0)).take(count));
+        self.corner_params.extend(iter::repeat(Point2D::new(0.0, 0.0)).take(count))
+    }
+
     fn add_vertices_for_rect(&mut self, context: &Context, rect: &Rect<Au>, z_value: f32) {
         let rect = rect.to_normalized_device_position(context);
         self.vertices.extend([
@@ -73,6 +129,25 @@ impl Batch {
         self.texture_coords.extend(iter::repeat(Point2D::new(0.0, 0.0)).take(count))
     }
 
+    fn add_mask_coords_for_rect(&mut self, mask_rect: &Rect<u32>) {
+        let (atlas_width, atlas_height) = (atlas::WIDTH as f32, atlas::HEIGHT as f32);
+        let mask_rect =
+            Rect::new(Point2D::new((mask_rect.origin.x as f32) / atlas_width,
+                                   (mask_rect.origin.y as f32) / atlas_height),
+                      Size2D::new((mask_rect.size.width as f32) / atlas_width,
+                                  (mask_rect.size.height as f32) / atlas_height));
+        self.mask_coords.extend([
+            mask_rect.origin,
+            mask_rect.top_right(),
+            mask_rect.bottom_left(),
+            mask_rect.bottom_right(),
+        ].iter());
+    }
+
+    fn add_dummy_mask_coords(&mut self, count: usize) {
+        self.mask_coords.extend(iter::repeat(OPAQUE_MASK_COORD).take(count))
+    }
+
     fn add_elements_for_clockwise_wound_rect(&mut self) {
         let bottom_right = self.vertices.len() as u32 - 1;
         let bottom_left = bottom_right - 1;
@@ -103,79 +178,858 @@ impl Batch {
         ].iter());
     }
 
-    // TODO(pcwalton): Only clear clips if we need to.
-    // TODO(pcwalton): Clip by adjusting vertices and texture coordinates for simple clips.
-    fn clear_clip(&mut self, context: &Context) {
-        let rect = Rect::new(Point2D::new(Au::from_px(0), Au::from_px(0)),
-                             context.render_target_size.to_au());
-        self.add_vertices_for_rect(context, &rect, FAR_DEPTH_VALUE);
-        self.add_solid_colors(4, &WHITE);
+    fn add_solid_color_rect(&mut self,
+                            context: &Context,
+                            rect: &Rect<Au>,
+                            color: &Color,
+                            clip: &ClipMask) {
+        self.add_vertices_for_rect(context, rect, self.depth);
+        self.add_solid_colors(4, color);
         self.add_dummy_buffer_gamma(4);
         self.add_dummy_texture_coords(4);
-        self.add_elements_for_clockwise_wound_rect();
+        self.add_mask_coords_for_item(rect, clip);
+        self.add_material(4, Material::SolidColor);
+        self.add_dummy_border_params(4);
+        self.add_elements_for_counterclockwise_wound_rect();
     }
 
-    // TODO(pcwalton): Only add clips if we need to.
-    // TODO(pcwalton): Clip by adjusting vertices and texture coordinates for simple clips.
-    fn add_clip(&mut self, context: &Context, clipping_region: &ClippingRegion) {
-        self.add_vertices_for_rect(context, &clipping_region.main, NEAR_DEPTH_VALUE);
-        self.add_solid_colors(4, &TRANSPARENT_GREEN);
-        self.add_dummy_buffer_gamma(4);
-        self.add_dummy_texture_coords(4);
-        self.add_elements_for_clockwise_wound_rect();
+    fn add_text(&mut self,
+                context: &mut Context,
+                bounds: &Rect<Au>,
+                asset: &mut Asset,
+                clip: &ClipMask) {
+        context.asset_manager.atlas.require_asset(asset, Priority::Retained);
+        let atlas_handle = asset.rasterization_status.get_atlas_handle();
+
+        self.add_vertices_for_rect(context, bounds, self.depth);
+        self.add_solid_colors(4, &BLACK);
+        self.add_buffer_gamma(4, 0.5, 0.01);
+        self.add_texture_coords_for_rect(&atlas_handle.borrow().location.rect);
+        self.add_mask_coords_for_item(bounds, clip);
+        self.add_material(4, Material::Text);
+        self.add_dummy_border_params(4);
+        self.add_elements_for_counterclockwise_wound_rect();
     }
 
-    fn add_solid_color_rect(&mut self, context: &Context, rect: &Rect<Au>, color: &Color) {
-        self.add_vertices_for_rect(context, rect, NEAR_DEPTH_VALUE);
+    fn add_box_shadow(&mut self,
+                      context: &mut Context,
+                      box_bounds: &Rect<Au>,
+                      color: &Color,
+                      blur_radius: Au,
+                      spread: Au,
+                      corner_radius: Au,
+                      clip: &ClipMask) {
+        // The blur spills `3*sigma` texels past the sharp edge, so the drawn quad is the
+        // spread-inflated box grown by the blur extent in every direction.
+        let sigma = (blur_radius.to_px() as f32) / 2.0;
+        let blur_extent = Au::from_px((3.0 * sigma).ceil() as i32);
+        let sharp_bounds = box_bounds.inflate(spread, spread);
+        let quad_bounds = sharp_bounds.inflate(blur_extent, blur_extent);
+
+        // An axis-aligned blurred rounded box is fully determined by its size, blur, and corner
+        // radius, so cache the resulting mask and reuse it across identical shadows.
+        let key = BoxShadowKey {
+            size: sharp_bounds.size,
+            blur_radius: blur_radius,
+            corner_radius: corner_radius,
+        };
+        let atlas_handle = match context.asset_manager.atlas.cached_box_shadow(&key) {
+            Some(handle) => handle,
+            None => {
+                let (size, coverage) = rasterize_box_shadow_mask(&sharp_bounds.size,
+                                                                 sigma,
+                                                                 corner_radius,
+                                                                 blur_extent);
+                let handle = context.asset_manager.atlas.allocate_mask(&size, &coverage);
+                context.asset_manager.atlas.cache_box_shadow(key, handle.clone());
+                handle
+            }
+        };
+
+        self.add_vertices_for_rect(context, &quad_bounds, self.depth);
         self.add_solid_colors(4, color);
         self.add_dummy_buffer_gamma(4);
+        self.add_texture_coords_for_rect(&atlas_handle.borrow().location.rect);
+        self.add_mask_coords_for_item(&quad_bounds, clip);
+        self.add_material(4, Material::Mask);
+        self.add_dummy_border_params(4);
+        self.add_elements_for_counterclockwise_wound_rect();
+    }
+
+    // Bakes `stops` into a 1-D ramp in the atlas, then emits a quad over `bounds` whose per-vertex
+    // texture coordinates sample that ramp at each corner's normalized position along the gradient
+    // axis. Radial and conic parameters are computed per corner and interpolated across the quad,
+    // matching WebRender's vertex-evaluated `ps_radial_gradient`/`ps_angle_gradient`.
+    fn add_gradient(&mut self,
+                    context: &mut Context,
+                    bounds: &Rect<Au>,
+                    stops: &[GradientStop],
+                    kind: &GradientKind,
+                    clip: &ClipMask) {
+        // A ramp depends only on its stops, so cache it the way the box-shadow path caches its mask
+        // and let N identical gradients share one atlas row.
+        let key = GradientKey {
+            stops: stops.iter().map(|stop| (stop.offset.to_bits(), stop.color)).collect(),
+        };
+        let atlas_handle = match context.asset_manager.atlas.cached_gradient(&key) {
+            Some(handle) => handle,
+            None => {
+                let ramp = build_gradient_ramp(stops);
+                let handle = context.asset_manager.atlas.allocate_ramp(&ramp);
+                context.asset_manager.atlas.cache_gradient(key, handle.clone());
+                handle
+            }
+        };
+        let ramp_rect = atlas_handle.borrow().location.rect;
+
+        // Map a gradient parameter in [0, 1] to a texture coordinate along the ramp row.
+        let (atlas_width, atlas_height) = (atlas::WIDTH as f32, atlas::HEIGHT as f32);
+        let ramp_v = (ramp_rect.origin.y as f32 + 0.5) / atlas_height;
+        let ramp_u = |t: f32| {
+            let t = t.max(0.0).min(1.0);
+            (ramp_rect.origin.x as f32 + t * (ramp_rect.size.width as f32 - 1.0) + 0.5) / atlas_width
+        };
+
+        self.add_vertices_for_rect(context, bounds, self.depth);
+        self.add_solid_colors(4, &WHITE);
+        self.add_dummy_buffer_gamma(4);
+        for corner in &[Point2D::new(bounds.origin.x, bounds.origin.y),
+                        Point2D::new(bounds.max_x(), bounds.origin.y),
+                        Point2D::new(bounds.origin.x, bounds.max_y()),
+                        Point2D::new(bounds.max_x(), bounds.max_y())] {
+            let t = gradient_parameter(kind, corner);
+            self.texture_coords.push(Point2D::new(ramp_u(t), ramp_v));
+        }
+        self.add_mask_coords_for_item(bounds, clip);
+        self.add_material(4, Material::Gradient);
+        self.add_dummy_border_params(4);
+        self.add_elements_for_counterclockwise_wound_rect();
+    }
+
+    // Decomposes a border into up to four edge quads and four corner quads, following WebRender's
+    // split into `ps_border_edge` and `ps_border_corner`. `radii` are the outer corner radii in the
+    // order top-left, top-right, bottom-right, bottom-left.
+    fn add_border(&mut self,
+                  context: &Context,
+                  bounds: &Rect<Au>,
+                  widths: &SideOffsets2D<Au>,
+                  sides: &[BorderSide; 4],
+                  radii: &[Au; 4],
+                  clip: &ClipMask) {
+        let (top, right, bottom, left) = (&sides[0], &sides[1], &sides[2], &sides[3]);
+        let (tl, tr, br, bl) = (radii[0], radii[1], radii[2], radii[3]);
+
+        // Edges run between the corner squares so corners own the rounded region.
+        let top_edge = Rect::new(Point2D::new(bounds.origin.x + tl, bounds.origin.y),
+                                 Size2D::new(bounds.size.width - tl - tr, widths.top));
+        let bottom_edge = Rect::new(Point2D::new(bounds.origin.x + bl,
+                                                 bounds.max_y() - widths.bottom),
+                                    Size2D::new(bounds.size.width - bl - br, widths.bottom));
+        let left_edge = Rect::new(Point2D::new(bounds.origin.x, bounds.origin.y + tl),
+                                  Size2D::new(widths.left, bounds.size.height - tl - bl));
+        let right_edge = Rect::new(Point2D::new(bounds.max_x() - widths.right,
+                                                bounds.origin.y + tr),
+                                   Size2D::new(widths.right, bounds.size.height - tr - br));
+        self.add_border_edge(context, &top_edge, top, clip);
+        self.add_border_edge(context, &bottom_edge, bottom, clip);
+        self.add_border_edge(context, &left_edge, left, clip);
+        self.add_border_edge(context, &right_edge, right, clip);
+
+        // Corners: outer radius from the item, inner radius stepped in by the adjacent edge widths.
+        // Each corner spans only its outward quadrant and blends its two adjacent sides' colors
+        // across the diagonal, the way CSS miters differently-colored sides together.
+        self.add_border_corner(context,
+                               &Point2D::new(bounds.origin.x + tl, bounds.origin.y + tl),
+                               (-1, -1),
+                               tl,
+                               widths.top.max(widths.left),
+                               &top.color,
+                               &left.color,
+                               clip);
+        self.add_border_corner(context,
+                               &Point2D::new(bounds.max_x() - tr, bounds.origin.y + tr),
+                               (1, -1),
+                               tr,
+                               widths.top.max(widths.right),
+                               &top.color,
+                               &right.color,
+                               clip);
+        self.add_border_corner(context,
+                               &Point2D::new(bounds.max_x() - br, bounds.max_y() - br),
+                               (1, 1),
+                               br,
+                               widths.bottom.max(widths.right),
+                               &bottom.color,
+                               &right.color,
+                               clip);
+        self.add_border_corner(context,
+                               &Point2D::new(bounds.origin.x + bl, bounds.max_y() - bl),
+                               (-1, 1),
+                               bl,
+                               widths.bottom.max(widths.left),
+                               &bottom.color,
+                               &left.color,
+                               clip);
+    }
+
+    fn add_border_edge(&mut self,
+                       context: &Context,
+                       rect: &Rect<Au>,
+                       side: &BorderSide,
+                       clip: &ClipMask) {
+        if rect.size.width <= Au::from_px(0) || rect.size.height <= Au::from_px(0) {
+            return
+        }
+
+        self.add_vertices_for_rect(context, rect, self.depth);
+        self.add_solid_colors(4, &side.color);
+        self.add_dummy_buffer_gamma(4);
         self.add_dummy_texture_coords(4);
+        self.add_mask_coords_for_item(rect, clip);
+        self.add_material(4, Material::BorderEdge);
+
+        // Dashes run along the longer axis; the fragment path discards gaps with
+        // `mod(pos_along_edge, period)`. A period of zero marks a solid edge.
+        let length = rect.size.width.max(rect.size.height).to_px() as f32;
+        let thickness = rect.size.width.min(rect.size.height).to_px() as f32;
+        let period = match side.style {
+            BorderStyle::Dashed => 3.0 * thickness,
+            BorderStyle::Dotted => 2.0 * thickness,
+            _ => 0.0,
+        };
+        // Ramp the position along the long axis so the pattern runs down the stroke, not across it:
+        // over X for horizontal edges, over Y for vertical ones. Vertices wind TL, TR, BL, BR.
+        let horizontal = rect.size.width >= rect.size.height;
+        let (tl, tr, bl, br) = if horizontal {
+            (0.0, length, 0.0, length)
+        } else {
+            (0.0, 0.0, length, length)
+        };
+        self.edge_params.extend([
+            Point2D::new(tl, period),
+            Point2D::new(tr, period),
+            Point2D::new(bl, period),
+            Point2D::new(br, period),
+        ].iter());
+        self.corner_params.extend(iter::repeat(Point2D::new(0.0, 0.0)).take(4));
         self.add_elements_for_counterclockwise_wound_rect();
     }
 
-    fn add_text(&mut self, context: &mut Context, bounds: &Rect<Au>, asset: &mut Asset) {
+    // Emits a border corner as the outward `r×r` quadrant only, split along the diagonal into two
+    // triangles so the adjacent horizontal and vertical sides each paint their half. `orientation` is
+    // the outward direction `(sx, sy)` in `{-1, 1}`. The fragment keeps the annulus `r_in <= d <=
+    // r_out` measured from the inset corner `center`; confining the quad to the outward quadrant stops
+    // the other three quarters of the ring from curving into the content box.
+    fn add_border_corner(&mut self,
+                         context: &Context,
+                         center: &Point2D<Au>,
+                         orientation: (i32, i32),
+                         outer_radius: Au,
+                         width: Au,
+                         horizontal_color: &Color,
+                         vertical_color: &Color,
+                         clip: &ClipMask) {
+        if outer_radius <= Au::from_px(0) {
+            return
+        }
+
+        let (sx, sy) = orientation;
+        let r_out = outer_radius.to_px() as f32;
+        let r_in = (outer_radius - width).to_px().max(0) as f32;
+
+        // Quadrant corners: `c` the inset center, `h`/`v` the neighbours along the two axes, `o` the
+        // outward corner. `h` is displaced along X so it lies on the vertical edge, `v` along Y so it
+        // lies on the horizontal edge; the diagonal `c`–`o` separates the two sides' halves.
+        let c = *center;
+        let h = Point2D::new(center.x + outer_radius * sx, center.y);
+        let v = Point2D::new(center.x, center.y + outer_radius * sy);
+        let o = Point2D::new(center.x + outer_radius * sx, center.y + outer_radius * sy);
+
+        let base = self.vertices.len() as u32;
+        for &(p, color) in &[(c, vertical_color), (h, vertical_color), (o, vertical_color),
+                             (c, horizontal_color), (v, horizontal_color), (o, horizontal_color)] {
+            self.add_border_corner_vertex(context, &p, center, r_in, r_out, color, clip);
+        }
+        self.elements.extend([
+            base, base + 1, base + 2,
+            base + 3, base + 4, base + 5,
+        ].iter());
+    }
+
+    // Pushes a single border-corner vertex across every channel: the inner and outer radii in the texture
+    // channel, the clip mask coordinate, and the position relative to the corner center in the corner
+    // channel so the fragment can recover `d = length(corner_params)`.
+    fn add_border_corner_vertex(&mut self,
+                                context: &Context,
+                                point: &Point2D<Au>,
+                                center: &Point2D<Au>,
+                                r_in: f32,
+                                r_out: f32,
+                                color: &Color,
+                                clip: &ClipMask) {
+        let ndc = point.to_normalized_device_position(context);
+        self.vertices.push(Point3D::new(ndc.x, -ndc.y, self.depth));
+        self.colors.push(*color);
+        self.buffer_gamma.push(Point2D::new(0.0, 0.0));
+        self.texture_coords.push(Point2D::new(r_in, r_out));
+        self.mask_coords.push(clip.mask_coord_for_point(point));
+        self.materials.push(Material::BorderCorner);
+        self.edge_params.push(Point2D::new(0.0, 0.0));
+        self.corner_params.push(Point2D::new((point.x - center.x).to_px() as f32,
+                                             (point.y - center.y).to_px() as f32));
+    }
+
+    // Emits an RGBA bitmap through the same textured-quad machinery as glyphs, but tinted white with
+    // no gamma correction. `tile_stride`, when set, repeats the image every `stride` device pixels by
+    // extending the texture coordinates past `[0, 1]`.
+    fn add_image(&mut self,
+                 context: &mut Context,
+                 bounds: &Rect<Au>,
+                 asset: &mut Asset,
+                 tile_stride: Option<Au>,
+                 clip: &ClipMask) {
         context.asset_manager.atlas.require_asset(asset, Priority::Retained);
         let atlas_handle = asset.rasterization_status.get_atlas_handle();
+        let texture_rect = atlas_handle.borrow().location.rect;
 
-        self.add_vertices_for_rect(context, bounds, NEAR_DEPTH_VALUE);
-        self.add_solid_colors(4, &BLACK);
-        self.add_buffer_gamma(4, 0.5, 0.01);
-        self.add_texture_coords_for_rect(&atlas_handle.borrow().location.rect);
+        self.add_vertices_for_rect(context, bounds, self.depth);
+        self.add_solid_colors(4, &WHITE);
+        self.add_dummy_buffer_gamma(4);
+        match tile_stride {
+            Some(stride) if stride > Au::from_px(0) => {
+                let repeat = Size2D::new(bounds.size.width.to_px() as f32 / stride.to_px() as f32,
+                                         bounds.size.height.to_px() as f32 / stride.to_px() as f32);
+                self.add_tiled_texture_coords_for_rect(&texture_rect, &repeat);
+            }
+            _ => self.add_texture_coords_for_rect(&texture_rect),
+        }
+        self.add_mask_coords_for_item(bounds, clip);
+        self.add_material(4, Material::Image);
+        self.add_dummy_border_params(4);
+        self.add_elements_for_counterclockwise_wound_rect();
+    }
+
+    // Emits a three-plane YUV frame. The planes may be uploaded at different resolutions; each is
+    // sampled from its own atlas region, with the Y plane in the texture channel and the U and V
+    // planes borrowing the edge and corner channels. The shader applies the `color_space` matrix.
+    fn add_yuv_image(&mut self,
+                     context: &mut Context,
+                     bounds: &Rect<Au>,
+                     y: &mut Asset,
+                     u: &mut Asset,
+                     v: &mut Asset,
+                     color_space: YuvColorSpace,
+                     clip: &ClipMask) {
+        let mut plane_rect = |asset: &mut Asset| {
+            context.asset_manager.atlas.require_asset(asset, Priority::Retained);
+            asset.rasterization_status.get_atlas_handle().borrow().location.rect
+        };
+        let (y_rect, u_rect, v_rect) = (plane_rect(y), plane_rect(u), plane_rect(v));
+
+        self.add_vertices_for_rect(context, bounds, self.depth);
+        self.add_solid_colors(4, &WHITE);
+        self.add_dummy_buffer_gamma(4);
+        self.add_texture_coords_for_rect(&y_rect);
+        self.add_mask_coords_for_item(bounds, clip);
+        self.add_material(4, Material::YuvImage(color_space));
+        self.edge_params.extend(atlas_rect_coords(&u_rect).iter());
+        self.corner_params.extend(atlas_rect_coords(&v_rect).iter());
         self.add_elements_for_counterclockwise_wound_rect();
     }
+
+    fn add_tiled_texture_coords_for_rect(&mut self, texture_rect: &Rect<u32>, repeat: &Size2D<f32>) {
+        let (atlas_width, atlas_height) = (atlas::WIDTH as f32, atlas::HEIGHT as f32);
+        let origin = Point2D::new(texture_rect.origin.x as f32 / atlas_width,
+                                  texture_rect.origin.y as f32 / atlas_height);
+        let size = Size2D::new(texture_rect.size.width as f32 / atlas_width * repeat.width,
+                               texture_rect.size.height as f32 / atlas_height * repeat.height);
+        self.texture_coords.extend([
+            origin,
+            Point2D::new(origin.x + size.width, origin.y),
+            Point2D::new(origin.x, origin.y + size.height),
+            Point2D::new(origin.x + size.width, origin.y + size.height),
+        ].iter());
+    }
+
+    // Emits the four mask texture coordinates for an item with the given device `bounds`, projecting
+    // each corner into the coverage tile produced for the active clip. When the clip carries no mask
+    // every corner gets the `OPAQUE_MASK_COORD` sentinel, so the fragment path skips the clip multiply.
+    fn add_mask_coords_for_item(&mut self, bounds: &Rect<Au>, clip: &ClipMask) {
+        if clip.mask_rect.is_none() {
+            return self.add_dummy_mask_coords(4)
+        }
+        self.mask_coords.extend([
+            clip.mask_coord_for_point(&Point2D::new(bounds.origin.x, bounds.origin.y)),
+            clip.mask_coord_for_point(&Point2D::new(bounds.max_x(), bounds.origin.y)),
+            clip.mask_coord_for_point(&Point2D::new(bounds.origin.x, bounds.max_y())),
+            clip.mask_coord_for_point(&Point2D::new(bounds.max_x(), bounds.max_y())),
+        ].iter());
+    }
+}
+
+// A resolved clip, ready to be sampled per-fragment. `bounds` is the device-space bounding rect of
+// the clipping region; `mask_rect` is the atlas tile holding its 8-bit coverage, or `None` when the
+// region needs no mask (an unclipped item, or a plain rectangle handled by the item bounds).
+#[derive(Clone, Copy)]
+struct ClipMask {
+    bounds: Rect<Au>,
+    mask_rect: Option<Rect<u32>>,
+}
+
+impl ClipMask {
+    // Rasterizes `clipping_region` into an 8-bit coverage tile in the mask atlas, ANDing together the
+    // complex rounded-rect clips and multiplying by the optional image mask, and returns a handle to
+    // it. Regions with neither complex clips nor a mask need no tile and use the opaque sentinel.
+    fn rasterize(context: &mut Context, clipping_region: &ClippingRegion) -> ClipMask {
+        let bounds = clipping_region.main;
+        if clipping_region.complex.is_empty() && clipping_region.image_mask.is_none() {
+            return ClipMask {
+                bounds: bounds,
+                mask_rect: None,
+            }
+        }
+
+        let size = Size2D::new(bounds.size.width.to_px() as u32,
+                               bounds.size.height.to_px() as u32);
+        let mut coverage = vec![0u8; (size.width * size.height) as usize];
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let p = Point2D::new(bounds.origin.x.to_px() as f32 + x as f32 + 0.5,
+                                     bounds.origin.y.to_px() as f32 + y as f32 + 0.5);
+                let mut alpha = 1.0f32;
+                for complex in &clipping_region.complex {
+                    alpha = alpha.min(rounded_rect_coverage(&p, complex));
+                }
+                if let Some(ref mask) = clipping_region.image_mask {
+                    alpha *= mask.sample(&p);
+                }
+                coverage[(y * size.width + x) as usize] = (alpha * 255.0 + 0.5) as u8;
+            }
+        }
+
+        let handle = context.asset_manager.atlas.allocate_mask(&size, &coverage);
+        ClipMask {
+            bounds: bounds,
+            mask_rect: Some(handle.borrow().location.rect),
+        }
+    }
+
+    // Projects a device-space `point` into the coverage tile, returning the atlas texture coordinate
+    // the fragment path samples. Unmasked clips return the opaque sentinel. The normalized position
+    // is clamped to `[0, 1]` so an item extending past the clip's main rect (e.g. a box shadow's
+    // blur-inflated quad) samples the tile edge instead of neighboring atlas content.
+    fn mask_coord_for_point(&self, point: &Point2D<Au>) -> Point2D<f32> {
+        let mask_rect = match self.mask_rect {
+            None => return OPAQUE_MASK_COORD,
+            Some(mask_rect) => mask_rect,
+        };
+        let (atlas_width, atlas_height) = (atlas::WIDTH as f32, atlas::HEIGHT as f32);
+        let u = ((point.x - self.bounds.origin.x).to_px() as f32 /
+            (self.bounds.size.width.to_px() as f32)).max(0.0).min(1.0);
+        let v = ((point.y - self.bounds.origin.y).to_px() as f32 /
+            (self.bounds.size.height.to_px() as f32)).max(0.0).min(1.0);
+        Point2D::new((mask_rect.origin.x as f32 + u * mask_rect.size.width as f32) / atlas_width,
+                     (mask_rect.origin.y as f32 + v * mask_rect.size.height as f32) / atlas_height)
+    }
+}
+
+// Coverage of the rounded rectangle `complex` at device-space point `p`, using the signed-distance
+// function of a rounded box and a one-texel-wide anti-aliased edge.
+fn rounded_rect_coverage(p: &Point2D<f32>, complex: &ComplexClipRegion) -> f32 {
+    let rect = &complex.rect;
+    let center = Point2D::new(rect.origin.x.to_px() as f32 + rect.size.width.to_px() as f32 * 0.5,
+                              rect.origin.y.to_px() as f32 + rect.size.height.to_px() as f32 * 0.5);
+    let half_size = Size2D::new(rect.size.width.to_px() as f32 * 0.5,
+                                rect.size.height.to_px() as f32 * 0.5);
+
+    // Pick the radius of the quadrant the point falls in.
+    let radius = match (p.x < center.x, p.y < center.y) {
+        (true, true) => complex.corner_radii[0],
+        (false, true) => complex.corner_radii[1],
+        (false, false) => complex.corner_radii[2],
+        (true, false) => complex.corner_radii[3],
+    };
+
+    let dx = ((p.x - center.x).abs() - (half_size.width - radius)).max(0.0);
+    let dy = ((p.y - center.y).abs() - (half_size.height - radius)).max(0.0);
+    let sd = (dx * dx + dy * dy).sqrt() - radius;
+    (0.5 - sd).max(0.0).min(1.0)
+}
+
+// Normalized atlas texture coordinates for the four corners of `rect`, in the quad vertex order
+// top-left, top-right, bottom-left, bottom-right.
+fn atlas_rect_coords(rect: &Rect<u32>) -> [Point2D<f32>; 4] {
+    let (atlas_width, atlas_height) = (atlas::WIDTH as f32, atlas::HEIGHT as f32);
+    let rect = Rect::new(Point2D::new(rect.origin.x as f32 / atlas_width,
+                                      rect.origin.y as f32 / atlas_height),
+                         Size2D::new(rect.size.width as f32 / atlas_width,
+                                     rect.size.height as f32 / atlas_height));
+    [rect.origin, rect.top_right(), rect.bottom_left(), rect.bottom_right()]
+}
+
+// Bakes gradient `stops` into a `GRADIENT_RAMP_LENGTH`-texel ramp, linearly interpolating between
+// adjacent stops in premultiplied-alpha space. Stops are assumed sorted by offset.
+fn build_gradient_ramp(stops: &[GradientStop]) -> Vec<Color> {
+    let mut ramp = Vec::with_capacity(GRADIENT_RAMP_LENGTH as usize);
+    for texel in 0..GRADIENT_RAMP_LENGTH {
+        let t = texel as f32 / (GRADIENT_RAMP_LENGTH as f32 - 1.0);
+        ramp.push(sample_stops(stops, t));
+    }
+    ramp
+}
+
+// Color of the gradient at normalized offset `t`, interpolating the bracketing stops in
+// premultiplied space so partially-transparent stops blend without fringing.
+fn sample_stops(stops: &[GradientStop], t: f32) -> Color {
+    match stops.first() {
+        None => return WHITE,
+        Some(first) if t <= first.offset => return first.color,
+        Some(_) => {}
+    }
+    for window in stops.windows(2) {
+        let (lo, hi) = (&window[0], &window[1]);
+        if t <= hi.offset {
+            let span = hi.offset - lo.offset;
+            let f = if span > 0.0 { (t - lo.offset) / span } else { 0.0 };
+            return lo.color.premultiply().lerp(&hi.color.premultiply(), f).unpremultiply()
+        }
+    }
+    stops[stops.len() - 1].color
+}
+
+// Normalized position of `point` along the axis of gradient `kind`, clamped to [0, 1] by the ramp
+// sampler. Linear projects onto `end - start`; radial divides distance-from-center by radius; conic
+// maps the angle around the center into `[0, 1)`.
+fn gradient_parameter(kind: &GradientKind, point: &Point2D<Au>) -> f32 {
+    use std::f32::consts::PI;
+    let p = Point2D::new(point.x.to_px() as f32, point.y.to_px() as f32);
+    match *kind {
+        GradientKind::Linear { start, end } => {
+            let start = Point2D::new(start.x.to_px() as f32, start.y.to_px() as f32);
+            let end = Point2D::new(end.x.to_px() as f32, end.y.to_px() as f32);
+            let axis = Point2D::new(end.x - start.x, end.y - start.y);
+            let len_sq = axis.x * axis.x + axis.y * axis.y;
+            if len_sq == 0.0 {
+                return 0.0
+            }
+            ((p.x - start.x) * axis.x + (p.y - start.y) * axis.y) / len_sq
+        }
+        GradientKind::Radial { center, radius } => {
+            let center = Point2D::new(center.x.to_px() as f32, center.y.to_px() as f32);
+            let radius = radius.to_px() as f32;
+            if radius == 0.0 {
+                return 0.0
+            }
+            ((p.x - center.x).hypot(p.y - center.y)) / radius
+        }
+        GradientKind::Conic { center, angle } => {
+            let center = Point2D::new(center.x.to_px() as f32, center.y.to_px() as f32);
+            let theta = (p.y - center.y).atan2(p.x - center.x) - angle;
+            let normalized = theta / (2.0 * PI);
+            normalized - normalized.floor()
+        }
+    }
+}
+
+// Identifies a baked gradient ramp by its stops (offset bit patterns paired with colors). Two
+// gradients with identical stops share one cached ramp regardless of their geometry.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct GradientKey {
+    pub stops: Vec<(u32, Color)>,
+}
+
+// Identifies a blurred rounded-box mask. Two box shadows with the same size, blur, and corner radius
+// share one cached mask regardless of their position or color.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BoxShadowKey {
+    pub size: Size2D<Au>,
+    pub blur_radius: Au,
+    pub corner_radius: Au,
+}
+
+// Rasterizes a sharp rounded-rect coverage mask for a box of `box_size`, padded by `blur_extent` on
+// every side, then blurs it with a two-pass separable Gaussian of the given `sigma`. Returns the
+// padded tile size and its 8-bit coverage.
+fn rasterize_box_shadow_mask(box_size: &Size2D<Au>,
+                             sigma: f32,
+                             corner_radius: Au,
+                             blur_extent: Au)
+                             -> (Size2D<u32>, Vec<u8>) {
+    let pad = blur_extent.to_px() as u32;
+    let size = Size2D::new(box_size.width.to_px() as u32 + 2 * pad,
+                           box_size.height.to_px() as u32 + 2 * pad);
+    let (w, h) = (size.width as usize, size.height as usize);
+    let radius = corner_radius.to_px() as f32;
+    let half = Size2D::new(box_size.width.to_px() as f32 * 0.5,
+                           box_size.height.to_px() as f32 * 0.5);
+    let center = Point2D::new(pad as f32 + half.width, pad as f32 + half.height);
+
+    // Sharp rounded-rect coverage.
+    let mut sharp = vec![0.0f32; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let p = Point2D::new(x as f32 + 0.5, y as f32 + 0.5);
+            let dx = ((p.x - center.x).abs() - (half.width - radius)).max(0.0);
+            let dy = ((p.y - center.y).abs() - (half.height - radius)).max(0.0);
+            let sd = (dx * dx + dy * dy).sqrt() - radius;
+            sharp[y * w + x] = (0.5 - sd).max(0.0).min(1.0);
+        }
+    }
+
+    // A spread-only shadow (`blur_radius == 0`, hence `sigma == 0`) has no blur to apply; copy the
+    // sharp mask straight through rather than dividing by a zero weight sum.
+    if sigma <= 0.0 {
+        let coverage = sharp.iter().map(|&c| (c * 255.0 + 0.5) as u8).collect();
+        return (size, coverage)
+    }
+
+    // Normalized Gaussian weights, sampling `ceil(3*sigma)` taps on each side.
+    let taps = (3.0 * sigma).ceil().max(1.0) as i32;
+    let mut weights = Vec::with_capacity((2 * taps + 1) as usize);
+    let mut sum = 0.0;
+    for x in -taps..(taps + 1) {
+        let w = (-(x * x) as f32 / (2.0 * sigma * sigma)).exp();
+        weights.push(w);
+        sum += w;
+    }
+    for w in &mut weights {
+        *w /= sum;
+    }
+
+    // Horizontal pass, then vertical pass.
+    let mut horizontal = vec![0.0f32; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let mut acc = 0.0;
+            for (i, weight) in weights.iter().enumerate() {
+                let sx = x as i32 + i as i32 - taps;
+                if sx >= 0 && (sx as usize) < w {
+                    acc += sharp[y * w + sx as usize] * weight;
+                }
+            }
+            horizontal[y * w + x] = acc;
+        }
+    }
+    let mut coverage = vec![0u8; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let mut acc = 0.0;
+            for (i, weight) in weights.iter().enumerate() {
+                let sy = y as i32 + i as i32 - taps;
+                if sy >= 0 && (sy as usize) < h {
+                    acc += horizontal[sy * w + x] * weight;
+                }
+            }
+            coverage[y * w + x] = (acc * 255.0 + 0.5) as u8;
+        }
+    }
+
+    (size, coverage)
+}
+
+// The texture resource a batch samples. Items that read the atlas cannot share a draw call with
+// items that read no texture, so this is part of the batch key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TextureSource {
+    None,
+    Atlas,
+}
+
+// Everything that forces a state change between draw calls: the shader material, the source texture,
+// and the clip mask tile. Items sharing a key coalesce into one index/vertex run.
+#[derive(Clone, Copy, PartialEq)]
+struct BatchKey {
+    material: Material,
+    texture: TextureSource,
+    clip: Option<Rect<u32>>,
+}
+
+impl Material {
+    // Texture the material reads from.
+    fn texture_source(self) -> TextureSource {
+        match self {
+            Material::SolidColor | Material::BorderEdge | Material::BorderCorner =>
+                TextureSource::None,
+            Material::Text | Material::Mask | Material::Gradient | Material::Image |
+            Material::YuvImage(..) => TextureSource::Atlas,
+        }
+    }
+
+    // Whether the material alpha-blends. Translucent items cannot be cross-material-merged, because
+    // compositing order is draw order, not depth order; the depth test only resolves opaque overlap.
+    fn is_translucent(self) -> bool {
+        match self {
+            Material::SolidColor | Material::BorderEdge | Material::BorderCorner |
+            Material::YuvImage(..) => false,
+            Material::Text | Material::Mask | Material::Gradient | Material::Image => true,
+        }
+    }
+}
+
+// Maps a display-list draw index into the depth range `[NEAR_DEPTH_VALUE, FAR_DEPTH_VALUE)`. Later
+// items get a nearer depth so they win the depth test, preserving painter's order even though
+// `batch_for` groups items by material rather than by position.
+const MAX_DRAW_INDEX: f32 = 65536.0;
+
+fn depth_for_draw_index(index: u32) -> f32 {
+    let t = ((index as f32) + 1.0).min(MAX_DRAW_INDEX) / MAX_DRAW_INDEX;
+    FAR_DEPTH_VALUE - t * (FAR_DEPTH_VALUE - NEAR_DEPTH_VALUE)
+}
+
+struct KeyedBatch {
+    key: BatchKey,
+    batch: Batch,
+    // Draw index of the first item placed in this batch, used to order batches in `finish` so their
+    // draw calls issue in display-list order.
+    first_draw_index: u32,
 }
 
 pub struct Batcher {
-    pending_batch: Batch,
+    batches: Vec<KeyedBatch>,
+    // The clip rasterized for the previous item, reused when the next item shares it so consecutive
+    // items under one clip don't re-rasterize the mask. (Resolves the old add/clear-clip TODO.)
+    current_clip: Option<(ClippingRegion, ClipMask)>,
+    // Monotonic display-list position, assigned to each item so its depth and its batch's order
+    // reflect draw order across material-grouped batches.
+    next_draw_index: u32,
 }
 
 impl Batcher {
     pub fn new() -> Batcher {
         Batcher {
-            pending_batch: Batch::new(),
+            batches: Vec::new(),
+            current_clip: None,
+            next_draw_index: 0,
         }
     }
 
     pub fn add(&mut self, context: &mut Context, display_item: &mut DisplayItem) {
-        self.pending_batch.clear_clip(context);
-        self.pending_batch.add_clip(context, &display_item.base().clip);
+        let draw_index = self.next_draw_index;
+        self.next_draw_index += 1;
+        let clip = self.clip_for(context, &display_item.base().clip);
 
         match *display_item {
             DisplayItem::SolidColor(ref mut solid_color_display_item) => {
-                self.pending_batch.add_solid_color_rect(context,
-                                                        &solid_color_display_item.base.bounds,
-                                                        &solid_color_display_item.color);
+                let batch = self.batch_for(Material::SolidColor, clip.mask_rect, draw_index);
+                batch.add_solid_color_rect(context,
+                                           &solid_color_display_item.base.bounds,
+                                           &solid_color_display_item.color,
+                                           &clip);
             }
             DisplayItem::Text(ref mut text_display_item) => {
-                self.pending_batch.add_text(context,
-                                            &text_display_item.base.bounds,
-                                            &mut *text_display_item.asset.borrow_mut());
+                let batch = self.batch_for(Material::Text, clip.mask_rect, draw_index);
+                batch.add_text(context,
+                               &text_display_item.base.bounds,
+                               &mut *text_display_item.asset.borrow_mut(),
+                               &clip);
+            }
+            DisplayItem::BoxShadow(ref box_shadow_display_item) => {
+                let batch = self.batch_for(Material::Mask, clip.mask_rect, draw_index);
+                batch.add_box_shadow(context,
+                                     &box_shadow_display_item.box_bounds,
+                                     &box_shadow_display_item.color,
+                                     box_shadow_display_item.blur_radius,
+                                     box_shadow_display_item.spread,
+                                     box_shadow_display_item.corner_radius,
+                                     &clip);
+            }
+            DisplayItem::Gradient(ref gradient_display_item) => {
+                let batch = self.batch_for(Material::Gradient, clip.mask_rect, draw_index);
+                batch.add_gradient(context,
+                                   &gradient_display_item.base.bounds,
+                                   &gradient_display_item.stops,
+                                   &gradient_display_item.kind,
+                                   &clip);
+            }
+            DisplayItem::Border(ref border_display_item) => {
+                // Edges and corners share the border shader family and select their path through the
+                // per-vertex material channel, so they batch together under one key.
+                let batch = self.batch_for(Material::BorderEdge, clip.mask_rect, draw_index);
+                batch.add_border(context,
+                                 &border_display_item.base.bounds,
+                                 &border_display_item.widths,
+                                 &border_display_item.sides,
+                                 &border_display_item.radii,
+                                 &clip);
+            }
+            DisplayItem::Image(ref image_display_item) => {
+                let batch = self.batch_for(Material::Image, clip.mask_rect, draw_index);
+                batch.add_image(context,
+                                &image_display_item.base.bounds,
+                                &mut *image_display_item.asset.borrow_mut(),
+                                image_display_item.tile_stride,
+                                &clip);
+            }
+            DisplayItem::YuvImage(ref yuv_image_display_item) => {
+                let color_space = yuv_image_display_item.color_space;
+                let batch = self.batch_for(Material::YuvImage(color_space),
+                                           clip.mask_rect,
+                                           draw_index);
+                batch.add_yuv_image(context,
+                                    &yuv_image_display_item.base.bounds,
+                                    &mut *yuv_image_display_item.y_plane.borrow_mut(),
+                                    &mut *yuv_image_display_item.u_plane.borrow_mut(),
+                                    &mut *yuv_image_display_item.v_plane.borrow_mut(),
+                                    color_space,
+                                    &clip);
+            }
+        }
+    }
+
+    // Rasterizes the clip for `clipping_region`, reusing the previous item's mask when the region is
+    // unchanged.
+    fn clip_for(&mut self, context: &mut Context, clipping_region: &ClippingRegion) -> ClipMask {
+        if let Some((ref region, mask)) = self.current_clip {
+            if region == clipping_region {
+                return mask
             }
         }
+        let mask = ClipMask::rasterize(context, clipping_region);
+        self.current_clip = Some((clipping_region.clone(), mask));
+        mask
+    }
+
+    // Returns the batch for `material` under clip `clip`, reusing an existing batch with the same key
+    // (so adjacent same-state items coalesce) or opening a new one. Stamps the batch's emit depth
+    // from `draw_index` so this item's quads sit at their display-list position in the depth buffer.
+    fn batch_for(&mut self, material: Material, clip: Option<Rect<u32>>, draw_index: u32)
+                 -> &mut Batch {
+        let key = BatchKey {
+            material: material,
+            texture: material.texture_source(),
+            clip: clip,
+        };
+        // Only opaque items coalesce into a shared batch; their overlap is resolved by the depth
+        // test. Translucent items must composite in draw order, so each gets its own batch and
+        // `finish` keeps them in sequence — merging them across materials would reorder the blend.
+        let existing = if material.is_translucent() {
+            None
+        } else {
+            self.batches.iter().position(|keyed| keyed.key == key)
+        };
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                self.batches.push(KeyedBatch {
+                    key: key,
+                    batch: Batch::new(),
+                    first_draw_index: draw_index,
+                });
+                self.batches.len() - 1
+            }
+        };
+        let keyed = &mut self.batches[index];
+        keyed.batch.depth = depth_for_draw_index(draw_index);
+        &mut keyed.batch
     }
 
+    // Returns the batches ordered by the draw index of their first item. Opaque batches coalesce many
+    // items and rely on the per-item depth stamp to resolve overlap; each translucent item is its own
+    // single-item batch, so ordering by draw index composites them strictly back-to-front.
     pub fn finish(self) -> Vec<Batch> {
-        vec![self.pending_batch]
+        let mut batches = self.batches;
+        batches.sort_by_key(|keyed| keyed.first_draw_index);
+        batches.into_iter().map(|keyed| keyed.batch).collect()
     }
 }
 